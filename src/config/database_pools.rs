@@ -1,27 +1,63 @@
 //! Configuration for setting up database pools
 //!
 //! - `DATABASE_URL`: The URL of the postgres database to use.
-//! - `READ_ONLY_REPLICA_URL`: The URL of an optional postgres read-only replica database.
+//! - `READ_ONLY_REPLICA_URLS`: A comma-separated list of URLs of optional postgres read-only
+//!   replica databases. `READ_ONLY_REPLICA_URL` (singular) is still accepted as an alias for a
+//!   single replica, for backwards compatibility.
 //! - `DB_PRIMARY_POOL_SIZE`: The number of connections of the primary database.
-//! - `DB_REPLICA_POOL_SIZE`: The number of connections of the read-only / replica database.
+//! - `DB_REPLICA_POOL_SIZE`: The number of connections of the read-only / replica database. Used
+//!   as the default for every replica; overridden per-replica by `DB_REPLICA_POOL_SIZE_{n}`.
 //! - `DB_PRIMARY_MIN_IDLE`: The primary pool will maintain at least this number of connections.
 //! - `DB_REPLICA_MIN_IDLE`: The replica pool will maintain at least this number of connections.
+//!   Used as the default for every replica; overridden per-replica by `DB_REPLICA_MIN_IDLE_{n}`.
+//! - `DB_REPLICA_LB_POLICY`: The policy used to pick a replica for a given read, either
+//!   `round-robin` (the default) or `weighted-random`.
+//! - `DB_REPLICA_WEIGHT_{n}`: The relative weight of the `n`th replica in
+//!   `READ_ONLY_REPLICA_URLS` when `DB_REPLICA_LB_POLICY=weighted-random`. Defaults to `1`.
+//!   Ignored by the round-robin policy.
+//! - `DB_PRIMARY_MAX_LIFETIME_SECS` / `DB_REPLICA_MAX_LIFETIME_SECS`: The maximum lifetime of a
+//!   pooled connection, in seconds. Connections older than this are closed and replaced. Unset by
+//!   default, meaning connections are kept forever. Overridden per-replica by
+//!   `DB_REPLICA_MAX_LIFETIME_SECS_{n}`.
+//! - `DB_PRIMARY_IDLE_TIMEOUT_SECS` / `DB_REPLICA_IDLE_TIMEOUT_SECS`: The maximum time a
+//!   connection may sit idle in the pool before being closed and replaced. Unset by default.
+//!   Overridden per-replica by `DB_REPLICA_IDLE_TIMEOUT_SECS_{n}`.
 //! - `DB_OFFLINE`: If set to `leader` then use the read-only follower as if it was the leader.
-//!   If set to `follower` then act as if `READ_ONLY_REPLICA_URL` was unset.
+//!   If set to `follower` then act as if `READ_ONLY_REPLICA_URLS` was unset.
 //! - `READ_ONLY_MODE`: If defined (even as empty) then force all connections to be read-only.
 //! - `DB_TCP_TIMEOUT_MS`: TCP timeout in milliseconds. See the doc comment for more details.
+//! - `DB_TEST_BEFORE_ACQUIRE`: If defined (even as empty), run a `SELECT 1` health check against a
+//!   connection before handing it out of the pool, replacing it if the check fails. Off by
+//!   default.
+//! - `DB_STATEMENT_TIMEOUT`: The server-side cancellation deadline for a running query, in
+//!   seconds. Falls back to `DB_TIMEOUT` if unset, for backwards compatibility.
+//!
+//! Settings can also be assembled in one place as a [`DatabasePoolsConfig`] (e.g. deserialized
+//! from a TOML or JSON file) and turned into a [`DatabasePools`] with
+//! [`DatabasePools::from_config`]. `full_from_environment` builds exactly such a value purely
+//! from the environment; [`DatabasePools::from_file_and_environment`] instead loads a base config
+//! from an optional file and layers the same environment variables on top as overrides.
 
 use crate::config::Base;
 use crate::{env, Env};
+use anyhow::Context;
 use secrecy::SecretString;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 
 pub struct DatabasePools {
     /// Settings for the primary database. This is usually writeable, but will be read-only in
     /// some configurations.
     pub primary: DbPoolConfig,
-    /// An optional follower database. Always read-only.
-    pub replica: Option<DbPoolConfig>,
+    /// Zero or more read-only follower databases. Always read-only. Reads are spread across
+    /// these according to `replica_lb_policy`.
+    pub replica: Vec<DbPoolConfig>,
+    /// The policy used to pick a replica out of `replica` for a given read.
+    pub replica_lb_policy: ReplicaLbPolicy,
     /// Number of seconds to wait for unacknowledged TCP packets before treating the connection as
     /// broken. This value will determine how long crates.io stays unavailable in case of full
     /// packet loss between the application and the database: setting it too high will result in an
@@ -39,6 +75,16 @@ pub struct DatabasePools {
     pub helper_threads: usize,
     /// Whether to enforce that all the database connections are encrypted with TLS.
     pub enforce_tls: bool,
+    /// Whether to run a lightweight health check (`SELECT 1`) against a connection before handing
+    /// it out of the pool, transparently discarding and replacing connections that fail it. This
+    /// catches connections whose TCP packets are being silently dropped at the exact moment
+    /// they're requested, complementing `tcp_timeout_ms`. Off by default, since it adds a
+    /// round-trip to every checkout.
+    pub test_before_acquire: bool,
+    /// Cursor used by `select_replica` to cycle through `replica` under `ReplicaLbPolicy::RoundRobin`.
+    /// Scoped to this instance, rather than a module-level counter, so that separate
+    /// `DatabasePools` (e.g. one per test) don't perturb each other's cycling order.
+    replica_round_robin: AtomicUsize,
 }
 
 #[derive(Debug)]
@@ -47,124 +93,543 @@ pub struct DbPoolConfig {
     pub read_only_mode: bool,
     pub pool_size: u32,
     pub min_idle: Option<u32>,
+    /// Relative weight of this replica when `replica_lb_policy` is `WeightedRandom`. Ignored by
+    /// the round-robin policy. Defaults to `1`.
+    pub weight: u32,
+    /// The maximum lifetime of a pooled connection. Connections older than this are closed and
+    /// replaced by the helper threads, which keeps the pool healthy across rolling database
+    /// restarts. `None` means connections are never retired due to age.
+    pub max_lifetime: Option<Duration>,
+    /// The maximum time a connection may sit idle in the pool before being closed and replaced.
+    /// `None` means idle connections are kept forever.
+    pub idle_timeout: Option<Duration>,
+}
+
+/// The policy used to spread reads across the configured replicas.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReplicaLbPolicy {
+    /// Cycle through the replicas in order, one per call to [`DatabasePools::select_replica`].
+    #[default]
+    RoundRobin,
+    /// Pick a replica at random, weighted by [`DbPoolConfig::weight`].
+    WeightedRandom,
+}
+
+impl ReplicaLbPolicy {
+    fn from_env() -> anyhow::Result<Option<Self>> {
+        match dotenvy::var("DB_REPLICA_LB_POLICY").as_deref() {
+            Ok("weighted-random") => Ok(Some(Self::WeightedRandom)),
+            Ok("round-robin") => Ok(Some(Self::RoundRobin)),
+            Ok(other) => anyhow::bail!("invalid DB_REPLICA_LB_POLICY: {other}"),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// A fully-structured, serde-deserializable representation of [`DatabasePools`] — e.g. loaded
+/// from a TOML or JSON config layer. Every field is optional so that a partial document can be
+/// layered with environment-variable overrides (see `from_file_and_environment`) before being
+/// validated and turned into a [`DatabasePools`] by [`DatabasePools::from_config`].
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DatabasePoolsConfig {
+    #[serde(default)]
+    pub primary: DbPoolConfigValue,
+    #[serde(default)]
+    pub replica: Vec<DbPoolConfigValue>,
+    pub replica_lb_policy: Option<ReplicaLbPolicy>,
+    pub tcp_timeout_ms: Option<u64>,
+    pub connection_timeout_secs: Option<u64>,
+    pub statement_timeout_secs: Option<u64>,
+    pub helper_threads: Option<usize>,
+    pub enforce_tls: Option<bool>,
+    pub test_before_acquire: Option<bool>,
+}
+
+/// The structured counterpart of [`DbPoolConfig`], with every field optional so it can represent
+/// "unset, use the default" when deserialized from a partial config document.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DbPoolConfigValue {
+    pub url: Option<String>,
+    pub read_only_mode: Option<bool>,
+    pub pool_size: Option<u32>,
+    pub min_idle: Option<u32>,
+    pub weight: Option<u32>,
+    pub max_lifetime_secs: Option<u64>,
+    pub idle_timeout_secs: Option<u64>,
 }
 
 impl DatabasePools {
     pub fn are_all_read_only(&self) -> bool {
         self.primary.read_only_mode
     }
+
+    /// Picks a replica to use for a read, following `replica_lb_policy`. Returns `None` if no
+    /// replicas are configured, in which case callers should fall back to the primary.
+    pub fn select_replica(&self) -> Option<&DbPoolConfig> {
+        match self.replica.as_slice() {
+            [] => None,
+            [only] => Some(only),
+            replicas => match self.replica_lb_policy {
+                ReplicaLbPolicy::RoundRobin => {
+                    let index =
+                        self.replica_round_robin.fetch_add(1, Ordering::Relaxed) % replicas.len();
+                    Some(&replicas[index])
+                }
+                ReplicaLbPolicy::WeightedRandom => {
+                    use rand::Rng;
+
+                    let total_weight: u32 = replicas.iter().map(|r| r.weight.max(1)).sum();
+                    let mut choice = rand::thread_rng().gen_range(0..total_weight);
+                    replicas
+                        .iter()
+                        .find(|replica| {
+                            let weight = replica.weight.max(1);
+                            if choice < weight {
+                                true
+                            } else {
+                                choice -= weight;
+                                false
+                            }
+                        })
+                        .or_else(|| replicas.last())
+                }
+            },
+        }
+    }
 }
 
 impl DatabasePools {
     const DEFAULT_POOL_SIZE: u32 = 3;
+    const DEFAULT_CONNECTION_TIMEOUT_SECS: u64 = 30;
+    const DEFAULT_TCP_TIMEOUT_MS: u64 = 15 * 1000;
+    const DEFAULT_HELPER_THREADS: usize = 3;
+
+    /// Builds a [`DatabasePools`] from a fully-structured config value, e.g. one deserialized
+    /// from a TOML or JSON file. Unset fields fall back to the same defaults used by
+    /// `full_from_environment`. Returns an error, rather than panicking, if a required field
+    /// (such as the primary database URL) is missing.
+    pub fn from_config(config: DatabasePoolsConfig, base: &Base) -> anyhow::Result<Self> {
+        let into_pool_config = |value: DbPoolConfigValue, read_only_mode, default_weight| {
+            anyhow::Ok(DbPoolConfig {
+                url: value.url.context("missing database URL")?.into(),
+                read_only_mode: value.read_only_mode.unwrap_or(read_only_mode),
+                pool_size: value.pool_size.unwrap_or(Self::DEFAULT_POOL_SIZE),
+                min_idle: value.min_idle,
+                weight: value.weight.unwrap_or(default_weight),
+                max_lifetime: value.max_lifetime_secs.map(Duration::from_secs),
+                idle_timeout: value.idle_timeout_secs.map(Duration::from_secs),
+            })
+        };
+
+        let primary = into_pool_config(config.primary, false, 1).context("invalid `primary`")?;
+
+        let replica = config
+            .replica
+            .into_iter()
+            .enumerate()
+            .map(|(index, value)| {
+                // Replicas are always opened read-only, whatever `read_only_mode` says.
+                let value = DbPoolConfigValue {
+                    read_only_mode: Some(true),
+                    ..value
+                };
+                into_pool_config(value, true, 1)
+                    .with_context(|| format!("invalid `replica[{index}]`"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let connection_timeout = Duration::from_secs(
+            config
+                .connection_timeout_secs
+                .unwrap_or(Self::DEFAULT_CONNECTION_TIMEOUT_SECS),
+        );
+
+        let statement_timeout = Duration::from_secs(
+            config
+                .statement_timeout_secs
+                .unwrap_or_else(|| connection_timeout.as_secs()),
+        );
+
+        Ok(Self {
+            primary,
+            replica,
+            replica_lb_policy: config.replica_lb_policy.unwrap_or_default(),
+            tcp_timeout_ms: config.tcp_timeout_ms.unwrap_or(Self::DEFAULT_TCP_TIMEOUT_MS),
+            connection_timeout,
+            statement_timeout,
+            helper_threads: config.helper_threads.unwrap_or(Self::DEFAULT_HELPER_THREADS),
+            enforce_tls: config.enforce_tls.unwrap_or(base.env == Env::Production),
+            test_before_acquire: config.test_before_acquire.unwrap_or(false),
+            replica_round_robin: AtomicUsize::new(0),
+        })
+    }
 
     /// Load settings for one or more database pools from the environment
     ///
-    /// # Panics
-    ///
-    /// This function panics if `DB_OFFLINE=leader` but `READ_ONLY_REPLICA_URL` is unset.
+    /// This assembles a [`DatabasePoolsConfig`] purely from environment variables and hands it
+    /// off to [`DatabasePools::from_config`], which is where the defaulting and validation
+    /// actually happens.
     pub fn full_from_environment(base: &Base) -> anyhow::Result<Self> {
-        let leader_url = env("DATABASE_URL").into();
-        let follower_url = dotenvy::var("READ_ONLY_REPLICA_URL").map(Into::into).ok();
-        let read_only_mode = dotenvy::var("READ_ONLY_MODE").is_ok();
+        let config = config_from_environment(Some(env("DATABASE_URL")))?;
+        Self::from_config(config, base)
+    }
 
-        let primary_pool_size = match dotenvy::var("DB_PRIMARY_POOL_SIZE") {
-            Ok(num) => num.parse().expect("couldn't parse DB_PRIMARY_POOL_SIZE"),
-            _ => Self::DEFAULT_POOL_SIZE,
+    /// Loads a [`DatabasePoolsConfig`] from `path`, if given, and layers the same environment
+    /// variables used by [`DatabasePools::full_from_environment`] on top of it as overrides,
+    /// before validating the merged result via [`DatabasePools::from_config`].
+    ///
+    /// `primary` is taken wholesale from the environment whenever `DATABASE_URL` is set (or
+    /// `DB_OFFLINE=leader` supplies a follower's URL), and `replica` whenever the environment
+    /// lists at least one replica URL, since those variables describe a complete topology rather
+    /// than a single tunable; each otherwise falls back to `path`'s value on its own. Every other,
+    /// scalar setting prefers its own environment variable when set and falls back to `path`'s
+    /// value otherwise.
+    pub fn from_file_and_environment(path: Option<&Path>, base: &Base) -> anyhow::Result<Self> {
+        let file_config = match path {
+            Some(path) => {
+                let contents = fs::read_to_string(path)
+                    .with_context(|| format!("couldn't read database pool config at {path:?}"))?;
+                serde_json::from_str(&contents)
+                    .with_context(|| format!("couldn't parse database pool config at {path:?}"))?
+            }
+            None => DatabasePoolsConfig::default(),
         };
 
-        let replica_pool_size = match dotenvy::var("DB_REPLICA_POOL_SIZE") {
-            Ok(num) => num.parse().expect("couldn't parse DB_REPLICA_POOL_SIZE"),
-            _ => Self::DEFAULT_POOL_SIZE,
-        };
+        let env_config = config_from_environment(dotenvy::var("DATABASE_URL").ok())?;
+        let config = layer_env_over_file(env_config, file_config);
 
-        let primary_min_idle = match dotenvy::var("DB_PRIMARY_MIN_IDLE") {
-            Ok(num) => Some(num.parse().expect("couldn't parse DB_PRIMARY_MIN_IDLE")),
-            _ => None,
-        };
+        Self::from_config(config, base)
+    }
+}
+
+/// Layers `env_config` (built by [`config_from_environment`]) on top of `file_config` (e.g.
+/// deserialized from a TOML or JSON file), preferring `env_config`'s value for each setting that
+/// it actually specifies.
+///
+/// `primary` is taken wholesale from `env_config` when it specifies a primary URL, and `replica`
+/// when it lists at least one replica, rather than merged field-by-field, since
+/// `READ_ONLY_REPLICA_URLS` et al. describe a complete topology rather than a single tunable.
+fn layer_env_over_file(
+    env_config: DatabasePoolsConfig,
+    file_config: DatabasePoolsConfig,
+) -> DatabasePoolsConfig {
+    DatabasePoolsConfig {
+        primary: if env_config.primary.url.is_some() {
+            env_config.primary
+        } else {
+            file_config.primary
+        },
+        replica: if env_config.replica.is_empty() {
+            file_config.replica
+        } else {
+            env_config.replica
+        },
+        replica_lb_policy: env_config.replica_lb_policy.or(file_config.replica_lb_policy),
+        tcp_timeout_ms: env_config.tcp_timeout_ms.or(file_config.tcp_timeout_ms),
+        connection_timeout_secs: env_config
+            .connection_timeout_secs
+            .or(file_config.connection_timeout_secs),
+        statement_timeout_secs: env_config
+            .statement_timeout_secs
+            .or(file_config.statement_timeout_secs),
+        helper_threads: env_config.helper_threads.or(file_config.helper_threads),
+        enforce_tls: env_config.enforce_tls.or(file_config.enforce_tls),
+        test_before_acquire: env_config
+            .test_before_acquire
+            .or(file_config.test_before_acquire),
+    }
+}
+
+/// Builds a [`DatabasePoolsConfig`] purely from environment variables, given `leader_url` (the
+/// result of resolving `DATABASE_URL`, whose required-ness differs between
+/// [`DatabasePools::full_from_environment`] and [`DatabasePools::from_file_and_environment`]).
+fn config_from_environment(leader_url: Option<String>) -> anyhow::Result<DatabasePoolsConfig> {
+    // `READ_ONLY_REPLICA_URLS` is the modern, plural env var. `READ_ONLY_REPLICA_URL`
+    // (singular) is kept around as an alias for a single replica, for backwards
+    // compatibility with existing deployments.
+    let follower_urls: Vec<String> = match dotenvy::var("READ_ONLY_REPLICA_URLS") {
+        Ok(urls) => urls
+            .split(',')
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty())
+            .collect(),
+        Err(_) => dotenvy::var("READ_ONLY_REPLICA_URL")
+            .into_iter()
+            .collect(),
+    };
+
+    let read_only_mode = dotenvy::var("READ_ONLY_MODE").is_ok();
+
+    let default_replica_pool_size = parse_env("DB_REPLICA_POOL_SIZE")?;
+    let default_replica_min_idle = parse_env("DB_REPLICA_MIN_IDLE")?;
+    let default_replica_max_lifetime_secs = parse_env("DB_REPLICA_MAX_LIFETIME_SECS")?;
+    let default_replica_idle_timeout_secs = parse_env("DB_REPLICA_IDLE_TIMEOUT_SECS")?;
+
+    // Per-replica overrides are indexed by position, e.g. `DB_REPLICA_POOL_SIZE_0` overrides
+    // the pool size of the first URL in `READ_ONLY_REPLICA_URLS`.
+    let mut replica = follower_urls
+        .into_iter()
+        .enumerate()
+        .map(|(index, url)| {
+            anyhow::Ok(DbPoolConfigValue {
+                url: Some(url),
+                read_only_mode: Some(true),
+                pool_size: parse_env(&format!("DB_REPLICA_POOL_SIZE_{index}"))?
+                    .or(default_replica_pool_size),
+                min_idle: parse_env(&format!("DB_REPLICA_MIN_IDLE_{index}"))?
+                    .or(default_replica_min_idle),
+                weight: parse_env(&format!("DB_REPLICA_WEIGHT_{index}"))?,
+                max_lifetime_secs: parse_env(&format!("DB_REPLICA_MAX_LIFETIME_SECS_{index}"))?
+                    .or(default_replica_max_lifetime_secs),
+                idle_timeout_secs: parse_env(&format!("DB_REPLICA_IDLE_TIMEOUT_SECS_{index}"))?
+                    .or(default_replica_idle_timeout_secs),
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut primary = DbPoolConfigValue {
+        url: leader_url,
+        read_only_mode: Some(read_only_mode),
+        pool_size: parse_env("DB_PRIMARY_POOL_SIZE")?,
+        min_idle: parse_env("DB_PRIMARY_MIN_IDLE")?,
+        weight: Some(1),
+        max_lifetime_secs: parse_env("DB_PRIMARY_MAX_LIFETIME_SECS")?,
+        idle_timeout_secs: parse_env("DB_PRIMARY_IDLE_TIMEOUT_SECS")?,
+    };
+
+    match dotenvy::var("DB_OFFLINE").as_deref() {
+        // The actual leader is down, use the first follower's URL in read-only mode as the
+        // primary (keeping the primary's own pool settings) and don't configure any
+        // replicas.
+        Ok("leader") => {
+            let follower = replica.drain(..).next().context(
+                "Must set `READ_ONLY_REPLICA_URLS` when using `DB_OFFLINE=leader`.",
+            )?;
+            primary.url = follower.url;
+            primary.read_only_mode = Some(true);
+        }
+        // The followers are down, don't configure any replicas.
+        Ok("follower") => replica.clear(),
+        _ => {}
+    }
+
+    let config = DatabasePoolsConfig {
+        primary,
+        replica,
+        replica_lb_policy: ReplicaLbPolicy::from_env()?,
+        tcp_timeout_ms: parse_env("DB_TCP_TIMEOUT_MS")?,
+        connection_timeout_secs: parse_env("DB_TIMEOUT")?,
+        // `DB_STATEMENT_TIMEOUT` is the dedicated knob for the server-side query cancellation
+        // deadline. It falls back to `DB_TIMEOUT` for backwards compatibility with
+        // deployments that relied on `DB_TIMEOUT` configuring both the connection timeout
+        // and the statement timeout; that fallback happens in `from_config`.
+        statement_timeout_secs: parse_env("DB_STATEMENT_TIMEOUT")?,
+        helper_threads: parse_env("DB_HELPER_THREADS")?,
+        enforce_tls: None,
+        // `None` here (rather than `Some(false)`) when the variable is unset, so that
+        // `from_file_and_environment` can tell "not set in the environment" apart from "set to
+        // off" and let a file-provided value show through.
+        test_before_acquire: dotenvy::var("DB_TEST_BEFORE_ACQUIRE").ok().map(|_| true),
+    };
+
+    Ok(config)
+}
+
+/// Parses an env var with a useful error message on failure, returning `Ok(None)` if it's unset.
+fn parse_env<T: FromStr>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match dotenvy::var(name) {
+        Ok(value) => Ok(Some(
+            value.parse().with_context(|| format!("couldn't parse {name}"))?,
+        )),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool_config(weight: u32) -> DbPoolConfig {
+        DbPoolConfig {
+            url: "postgres://localhost/test".to_string().into(),
+            read_only_mode: true,
+            pool_size: 1,
+            min_idle: None,
+            weight,
+            max_lifetime: None,
+            idle_timeout: None,
+        }
+    }
+
+    fn pools_with_replicas(replica_lb_policy: ReplicaLbPolicy, weights: &[u32]) -> DatabasePools {
+        DatabasePools {
+            primary: pool_config(1),
+            replica: weights.iter().copied().map(pool_config).collect(),
+            replica_lb_policy,
+            tcp_timeout_ms: 15_000,
+            connection_timeout: Duration::from_secs(30),
+            statement_timeout: Duration::from_secs(30),
+            helper_threads: 3,
+            enforce_tls: false,
+            test_before_acquire: false,
+            replica_round_robin: AtomicUsize::new(0),
+        }
+    }
+
+    #[test]
+    fn select_replica_returns_none_without_replicas() {
+        let pools = pools_with_replicas(ReplicaLbPolicy::RoundRobin, &[]);
+        assert!(pools.select_replica().is_none());
+    }
+
+    #[test]
+    fn select_replica_returns_the_only_replica() {
+        let pools = pools_with_replicas(ReplicaLbPolicy::WeightedRandom, &[42]);
+        assert_eq!(pools.select_replica().unwrap().weight, 42);
+    }
+
+    #[test]
+    fn select_replica_round_robin_cycles_in_order() {
+        // Scoped to this `DatabasePools` instance, so running alongside other tests in the same
+        // binary can't perturb the cycling order.
+        let pools = pools_with_replicas(ReplicaLbPolicy::RoundRobin, &[10, 20, 30]);
+        let picks: Vec<u32> = (0..6)
+            .map(|_| pools.select_replica().unwrap().weight)
+            .collect();
+        assert_eq!(picks, vec![10, 20, 30, 10, 20, 30]);
+    }
+
+    #[test]
+    fn select_replica_weighted_random_favors_higher_weight() {
+        let pools = pools_with_replicas(ReplicaLbPolicy::WeightedRandom, &[1, 99]);
+        let heavy_picks = (0..1000)
+            .filter(|_| pools.select_replica().unwrap().weight == 99)
+            .count();
+        assert!(
+            heavy_picks > 900,
+            "expected the weight=99 replica to dominate, got {heavy_picks}/1000"
+        );
+    }
+
+    fn test_base() -> Base {
+        Base {
+            env: Env::Development,
+        }
+    }
+
+    #[test]
+    fn database_pools_config_defaults_primary_when_omitted() {
+        let config: DatabasePoolsConfig = serde_json::from_str("{}").unwrap();
+        assert!(config.primary.url.is_none());
+    }
+
+    #[test]
+    fn database_pools_config_rejects_unknown_fields() {
+        let result: Result<DatabasePoolsConfig, _> = serde_json::from_str(
+            r#"{"primary": {"url": "postgres://x"}, "totally_not_a_field": true}"#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn from_config_requires_primary_url() {
+        let config = DatabasePoolsConfig::default();
+        let err = DatabasePools::from_config(config, &test_base()).unwrap_err();
+        assert!(err.to_string().contains("invalid `primary`"));
+    }
 
-        let replica_min_idle = match dotenvy::var("DB_REPLICA_MIN_IDLE") {
-            Ok(num) => Some(num.parse().expect("couldn't parse DB_REPLICA_MIN_IDLE")),
-            _ => None,
+    #[test]
+    fn from_config_reports_invalid_replica_index() {
+        let config = DatabasePoolsConfig {
+            primary: DbPoolConfigValue {
+                url: Some("postgres://leader".to_string()),
+                ..Default::default()
+            },
+            replica: vec![DbPoolConfigValue::default()],
+            ..Default::default()
         };
+        let err = DatabasePools::from_config(config, &test_base()).unwrap_err();
+        assert!(err.to_string().contains("invalid `replica[0]`"));
+    }
 
-        let tcp_timeout_ms = match dotenvy::var("DB_TCP_TIMEOUT_MS") {
-            Ok(num) => num.parse().expect("couldn't parse DB_TCP_TIMEOUT_MS"),
-            Err(_) => 15 * 1000, // 15 seconds
+    #[test]
+    fn from_config_applies_defaults() {
+        let config = DatabasePoolsConfig {
+            primary: DbPoolConfigValue {
+                url: Some("postgres://leader".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
         };
+        let pools = DatabasePools::from_config(config, &test_base()).unwrap();
+        assert_eq!(pools.primary.pool_size, DatabasePools::DEFAULT_POOL_SIZE);
+        assert!(pools.replica.is_empty());
+        assert_eq!(pools.replica_lb_policy, ReplicaLbPolicy::RoundRobin);
+        assert!(!pools.test_before_acquire);
+    }
 
-        let connection_timeout = match dotenvy::var("DB_TIMEOUT") {
-            Ok(num) => num.parse().expect("couldn't parse DB_TIMEOUT"),
-            _ => 30,
+    #[test]
+    fn layer_env_over_file_prefers_file_primary_when_env_unset() {
+        let file_config = DatabasePoolsConfig {
+            primary: DbPoolConfigValue {
+                url: Some("postgres://from-file".to_string()),
+                ..Default::default()
+            },
+            tcp_timeout_ms: Some(1234),
+            ..Default::default()
         };
-        let connection_timeout = Duration::from_secs(connection_timeout);
+        let env_config = DatabasePoolsConfig::default();
 
-        // `DB_TIMEOUT` currently configures both the connection timeout and
-        // the statement timeout, so we can copy the parsed connection timeout.
-        let statement_timeout = connection_timeout;
+        let merged = layer_env_over_file(env_config, file_config);
 
-        let helper_threads = match dotenvy::var("DB_HELPER_THREADS") {
-            Ok(num) => num.parse().expect("couldn't parse DB_HELPER_THREADS"),
-            _ => 3,
-        };
+        assert_eq!(merged.primary.url.as_deref(), Some("postgres://from-file"));
+        assert_eq!(merged.tcp_timeout_ms, Some(1234));
+    }
 
-        let enforce_tls = base.env == Env::Production;
-
-        Ok(match dotenvy::var("DB_OFFLINE").as_deref() {
-            // The actual leader is down, use the follower in read-only mode as the primary and
-            // don't configure a replica.
-            Ok("leader") => Self {
-                primary: DbPoolConfig {
-                    url: follower_url
-                        .expect("Must set `READ_ONLY_REPLICA_URL` when using `DB_OFFLINE=leader`."),
-                    read_only_mode: true,
-                    pool_size: primary_pool_size,
-                    min_idle: primary_min_idle,
-                },
-                replica: None,
-                tcp_timeout_ms,
-                connection_timeout,
-                statement_timeout,
-                helper_threads,
-                enforce_tls,
-            },
-            // The follower is down, don't configure the replica.
-            Ok("follower") => Self {
-                primary: DbPoolConfig {
-                    url: leader_url,
-                    read_only_mode,
-                    pool_size: primary_pool_size,
-                    min_idle: primary_min_idle,
-                },
-                replica: None,
-                tcp_timeout_ms,
-                connection_timeout,
-                statement_timeout,
-                helper_threads,
-                enforce_tls,
+    #[test]
+    fn layer_env_over_file_prefers_env_primary_when_set() {
+        let file_config = DatabasePoolsConfig {
+            primary: DbPoolConfigValue {
+                url: Some("postgres://from-file".to_string()),
+                ..Default::default()
             },
-            _ => Self {
-                primary: DbPoolConfig {
-                    url: leader_url,
-                    read_only_mode,
-                    pool_size: primary_pool_size,
-                    min_idle: primary_min_idle,
-                },
-                replica: follower_url.map(|url| DbPoolConfig {
-                    url,
-                    // Always enable read-only mode for the follower. In staging, we attach the
-                    // same leader database to both environment variables and this ensures the
-                    // connection is opened read-only even when attached to a writeable database.
-                    read_only_mode: true,
-                    pool_size: replica_pool_size,
-                    min_idle: replica_min_idle,
-                }),
-                tcp_timeout_ms,
-                connection_timeout,
-                statement_timeout,
-                helper_threads,
-                enforce_tls,
+            ..Default::default()
+        };
+        let env_config = DatabasePoolsConfig {
+            primary: DbPoolConfigValue {
+                url: Some("postgres://from-env".to_string()),
+                ..Default::default()
             },
-        })
+            ..Default::default()
+        };
+
+        let merged = layer_env_over_file(env_config, file_config);
+
+        assert_eq!(merged.primary.url.as_deref(), Some("postgres://from-env"));
+    }
+
+    #[test]
+    fn layer_env_over_file_prefers_env_scalar_settings_when_set() {
+        let file_config = DatabasePoolsConfig {
+            tcp_timeout_ms: Some(1234),
+            test_before_acquire: Some(true),
+            ..Default::default()
+        };
+        let env_config = DatabasePoolsConfig {
+            tcp_timeout_ms: Some(5678),
+            ..Default::default()
+        };
+
+        let merged = layer_env_over_file(env_config, file_config);
+
+        assert_eq!(merged.tcp_timeout_ms, Some(5678));
+        // `test_before_acquire` wasn't set in the environment, so the file's value shows through.
+        assert_eq!(merged.test_before_acquire, Some(true));
     }
 }
@@ -0,0 +1,102 @@
+//! Helpers for turning a [`DbPoolConfig`] into an actual r2d2 connection pool.
+
+use crate::config::{DatabasePools, DbPoolConfig};
+use crate::metrics::{self, PoolMetrics};
+use diesel::connection::SimpleConnection;
+use diesel::r2d2::{self, ConnectionManager, Pool};
+use diesel::PgConnection;
+use secrecy::ExposeSecret;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Builds a connection pool for `config`, wires up its metrics under `pool_name`, and spawns the
+/// background thread that keeps the total/idle/in-use gauges current.
+///
+/// `metrics` should be a single [`PoolMetrics`] shared by every pool built against a given
+/// `Registry` (typically created once at app startup) — `build_pool` only ever reads label-scoped
+/// handles out of it via [`PoolMetrics::for_pool`], so calling this more than once for the same
+/// `pool_name` (e.g. rebuilding a pool after a config reload) is safe and never double-registers a
+/// metric.
+///
+/// The returned pool is `Arc`-wrapped so the background poller thread can hold a [`std::sync::Weak`]
+/// reference to it rather than an owned clone: once every `Arc` handed back here is dropped, the
+/// poller notices on its next wake-up and exits instead of keeping the pool (and its connections)
+/// alive forever.
+pub fn build_pool(
+    pool_name: &str,
+    config: &DbPoolConfig,
+    pools: &DatabasePools,
+    metrics: &PoolMetrics,
+) -> anyhow::Result<Arc<Pool<ConnectionManager<PgConnection>>>> {
+    let pool_metrics = metrics.for_pool(pool_name);
+
+    let manager = ConnectionManager::<PgConnection>::new(config.url.expose_secret());
+
+    let pool = pool_builder(config, pools)
+        .event_handler(Box::new(metrics::PoolEventHandler::new(pool_metrics.clone())))
+        .build(manager)?;
+    let pool = Arc::new(pool);
+
+    metrics::spawn_state_poller(Arc::downgrade(&pool), pool_metrics);
+
+    Ok(pool)
+}
+
+/// Builds an r2d2 pool builder pre-configured from a [`DbPoolConfig`], leaving the caller free to
+/// call `.build(manager)` once a `ConnectionManager` is available.
+///
+/// `pools` is consulted for settings that apply across every pool rather than to this one
+/// specifically, such as `test_before_acquire` and `statement_timeout`.
+pub fn pool_builder(
+    config: &DbPoolConfig,
+    pools: &DatabasePools,
+) -> diesel::r2d2::Builder<ConnectionManager<PgConnection>> {
+    let mut builder = Pool::builder()
+        .max_size(config.pool_size)
+        .connection_timeout(pools.connection_timeout)
+        // `ConnectionManager::is_valid` runs a lightweight `SELECT 1`, so this is exactly the
+        // health check described by `DatabasePools::test_before_acquire`: a broken connection is
+        // transparently discarded and replaced at checkout time instead of being handed to the
+        // caller.
+        .test_on_check_out(pools.test_before_acquire)
+        .connection_customizer(Box::new(ConnectionConfig {
+            statement_timeout: pools.statement_timeout,
+            read_only: config.read_only_mode,
+        }));
+
+    if let Some(min_idle) = config.min_idle {
+        builder = builder.min_idle(Some(min_idle));
+    }
+
+    // Passed through as-is, `None` included: r2d2's own defaults (30 minutes / 10 minutes) would
+    // otherwise retire connections even when neither setting is configured.
+    builder
+        .max_lifetime(config.max_lifetime)
+        .idle_timeout(config.idle_timeout)
+}
+
+/// Applies per-connection settings the moment a connection is created, rather than relying on
+/// each caller to remember to set them.
+#[derive(Debug)]
+struct ConnectionConfig {
+    statement_timeout: Duration,
+    read_only: bool,
+}
+
+impl r2d2::CustomizeConnection<PgConnection, r2d2::Error> for ConnectionConfig {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), r2d2::Error> {
+        // `DB_STATEMENT_TIMEOUT` (or the `DB_TIMEOUT` fallback) is applied here, as its own `SET`
+        // statement, so long-running queries are cancelled at the database rather than merely
+        // timing out the pool checkout.
+        let statement_timeout_ms = self.statement_timeout.as_millis();
+        conn.batch_execute(&format!("SET statement_timeout = {statement_timeout_ms}"))
+            .map_err(r2d2::Error::QueryError)?;
+
+        if self.read_only {
+            conn.batch_execute("SET default_transaction_read_only = 't'")
+                .map_err(r2d2::Error::QueryError)?;
+        }
+
+        Ok(())
+    }
+}
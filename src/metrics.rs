@@ -0,0 +1,209 @@
+//! Metrics for observing the behavior of our database connection pools at runtime.
+//!
+//! Each configured pool (the primary, and each replica) is broken out by a `pool` label, so
+//! operators can alarm on a specific pool's saturation and spot the "unhealthy database"
+//! condition the `tcp_timeout_ms` doc comment references, rather than just the aggregate across
+//! all of them.
+//!
+//! [`PoolMetrics`] registers its metric *families* with the registry exactly once (typically at
+//! app startup). [`PoolMetrics::for_pool`] is cheap and idempotent to call afterwards — including
+//! more than once for the same pool name, e.g. across a config-reload that rebuilds a pool — since
+//! it goes through `with_label_values` rather than registering anything new.
+
+use diesel::r2d2::{self, ConnectionManager, HandleEvent};
+use diesel::PgConnection;
+use prometheus::{Histogram, HistogramOpts, HistogramVec, IntGauge, IntGaugeVec, Opts, Registry};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+/// Holds the metric families shared by every pool. Construct one of these per `Registry` (e.g.
+/// once at app startup) and pass it to every `db::build_pool` call.
+pub struct PoolMetrics {
+    total_connections: IntGaugeVec,
+    idle_connections: IntGaugeVec,
+    in_use_connections: IntGaugeVec,
+    connection_acquire_duration: HistogramVec,
+    connection_create_duration: HistogramVec,
+}
+
+impl PoolMetrics {
+    /// Creates the metric families and registers them with `registry`. Call this once per
+    /// registry; registering the same families twice against the same registry is a
+    /// `prometheus::Error::AlreadyReg`.
+    pub fn new(registry: &Registry) -> anyhow::Result<Self> {
+        let total_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_total_connections",
+                "Total number of connections managed by the pool",
+            ),
+            &["pool"],
+        )?;
+
+        let idle_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_idle_connections",
+                "Number of connections currently idle in the pool",
+            ),
+            &["pool"],
+        )?;
+
+        let in_use_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_in_use_connections",
+                "Number of connections currently checked out of the pool",
+            ),
+            &["pool"],
+        )?;
+
+        let connection_acquire_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "db_pool_connection_acquire_duration_seconds",
+                "Time spent waiting for a connection to be checked out of the pool",
+            ),
+            &["pool"],
+        )?;
+
+        let connection_create_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "db_pool_connection_create_duration_seconds",
+                "Time spent establishing a new database connection",
+            ),
+            &["pool"],
+        )?;
+
+        registry.register(Box::new(total_connections.clone()))?;
+        registry.register(Box::new(idle_connections.clone()))?;
+        registry.register(Box::new(in_use_connections.clone()))?;
+        registry.register(Box::new(connection_acquire_duration.clone()))?;
+        registry.register(Box::new(connection_create_duration.clone()))?;
+
+        Ok(Self {
+            total_connections,
+            idle_connections,
+            in_use_connections,
+            connection_acquire_duration,
+            connection_create_duration,
+        })
+    }
+
+    /// Returns the metric instances for a single pool, identified by `pool_name`. Safe to call
+    /// more than once for the same name: `with_label_values` reuses the existing child metric
+    /// instead of registering a new one.
+    pub fn for_pool(&self, pool_name: &str) -> PoolMetricsHandle {
+        let labels = [pool_name];
+        PoolMetricsHandle {
+            total_connections: self.total_connections.with_label_values(&labels),
+            idle_connections: self.idle_connections.with_label_values(&labels),
+            in_use_connections: self.in_use_connections.with_label_values(&labels),
+            connection_acquire_duration: self.connection_acquire_duration.with_label_values(&labels),
+            connection_create_duration: self.connection_create_duration.with_label_values(&labels),
+        }
+    }
+}
+
+/// The metric instances for a single pool, e.g. the primary or a specific replica.
+#[derive(Clone)]
+pub struct PoolMetricsHandle {
+    total_connections: IntGauge,
+    idle_connections: IntGauge,
+    in_use_connections: IntGauge,
+    connection_acquire_duration: Histogram,
+    connection_create_duration: Histogram,
+}
+
+/// An r2d2 event handler that feeds connection lifecycle events into a [`PoolMetricsHandle`].
+#[derive(Clone)]
+pub struct PoolEventHandler {
+    metrics: PoolMetricsHandle,
+}
+
+impl PoolEventHandler {
+    pub fn new(metrics: PoolMetricsHandle) -> Self {
+        Self { metrics }
+    }
+}
+
+impl std::fmt::Debug for PoolEventHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PoolEventHandler").finish()
+    }
+}
+
+impl HandleEvent for PoolEventHandler {
+    fn handle_acquire(&self, event: r2d2::event::AcquireEvent) {
+        self.metrics
+            .connection_create_duration
+            .observe(event.duration().as_secs_f64());
+    }
+
+    fn handle_checkout(&self, event: r2d2::event::CheckoutEvent) {
+        self.metrics
+            .connection_acquire_duration
+            .observe(event.duration().as_secs_f64());
+    }
+}
+
+/// How often the background poller re-samples `pool.state()`.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Spawns a background thread, analogous to r2d2's own helper threads, that periodically samples
+/// `pool.state()` and updates the total/idle/in-use gauges. r2d2 doesn't emit events for
+/// connections merely sitting in the pool, so polling is the simplest way to keep these gauges
+/// current.
+///
+/// Takes a `Weak` handle rather than an owned `Pool`, so the thread doesn't itself keep the pool
+/// (and every connection in it) alive: once the last strong handle returned by `db::build_pool` is
+/// dropped, `pool.upgrade()` starts returning `None` and the thread exits on its next wake-up.
+pub fn spawn_state_poller(
+    pool: Weak<r2d2::Pool<ConnectionManager<PgConnection>>>,
+    metrics: PoolMetricsHandle,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        let Some(pool) = pool.upgrade() else {
+            break;
+        };
+
+        let state = pool.state();
+        metrics.total_connections.set(state.connections as i64);
+        metrics.idle_connections.set(state.idle_connections as i64);
+        metrics
+            .in_use_connections
+            .set((state.connections - state.idle_connections) as i64);
+
+        drop(pool);
+        thread::sleep(POLL_INTERVAL);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn for_pool_is_idempotent_for_the_same_name() {
+        let registry = Registry::new();
+        let metrics = PoolMetrics::new(&registry).unwrap();
+
+        let first = metrics.for_pool("primary");
+        let second = metrics.for_pool("primary");
+
+        first.total_connections.set(7);
+        assert_eq!(second.total_connections.get(), 7);
+    }
+
+    #[test]
+    fn for_pool_scopes_distinct_names_independently() {
+        let registry = Registry::new();
+        let metrics = PoolMetrics::new(&registry).unwrap();
+
+        let primary = metrics.for_pool("primary");
+        let replica = metrics.for_pool("replica-0");
+
+        primary.total_connections.set(3);
+        replica.total_connections.set(9);
+
+        assert_eq!(primary.total_connections.get(), 3);
+        assert_eq!(replica.total_connections.get(), 9);
+    }
+}